@@ -0,0 +1,94 @@
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension, Result};
+
+use crate::Image;
+
+// Connection is behind a Mutex since rusqlite::Connection is Send but not
+// Sync, and &DbCtx gets shared across threads (scan task joins, http handlers).
+pub struct DbCtx {
+    conn: Mutex<Connection>,
+}
+
+impl DbCtx {
+    pub fn open(path: &str) -> Result<DbCtx> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS images (
+                id TEXT PRIMARY KEY,
+                name TEXT NOT NULL,
+                host TEXT NOT NULL DEFAULT ''
+            );
+            CREATE TABLE IF NOT EXISTS scans (
+                image_id TEXT NOT NULL,
+                scanned_at INTEGER NOT NULL,
+                vulnerable INTEGER NOT NULL,
+                report_path TEXT
+            );
+            CREATE INDEX IF NOT EXISTS scans_image_id ON scans (image_id, scanned_at);",
+        )?;
+
+        Ok(DbCtx { conn: Mutex::new(conn) })
+    }
+
+    pub fn record_scan(&self, image: &Image, vulnerable: bool, report_path: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+
+        conn.execute(
+            "INSERT INTO images (id, name, host) VALUES (?1, ?2, ?3)
+             ON CONFLICT(id) DO UPDATE SET name = excluded.name, host = excluded.host",
+            params![image.id, image.name, image.host],
+        )?;
+
+        conn.execute(
+            "INSERT INTO scans (image_id, scanned_at, vulnerable, report_path)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![image.id, Utc::now().timestamp(), vulnerable as i64, report_path],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn currently_vulnerable_ids(&self) -> Result<HashSet<String>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT s.image_id FROM scans s
+             INNER JOIN (
+                 SELECT image_id, MAX(scanned_at) AS latest
+                 FROM scans GROUP BY image_id
+             ) last ON s.image_id = last.image_id AND s.scanned_at = last.latest
+             WHERE s.vulnerable = 1",
+        )?;
+
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        rows.collect()
+    }
+
+    pub fn latest_scan_states(&self) -> Result<Vec<(String, String, String, bool)>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT i.id, i.name, i.host, s.vulnerable FROM scans s
+             INNER JOIN (
+                 SELECT image_id, MAX(scanned_at) AS latest
+                 FROM scans GROUP BY image_id
+             ) last ON s.image_id = last.image_id AND s.scanned_at = last.latest
+             INNER JOIN images i ON i.id = s.image_id",
+        )?;
+
+        let rows = stmt.query_map([], |row| {
+            let vulnerable : i64 = row.get(3)?;
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?, vulnerable != 0))
+        })?;
+        rows.collect()
+    }
+
+    pub fn image_info(&self, id: &str) -> Result<Option<(String, String)>> {
+        self.conn.lock().unwrap()
+            .query_row("SELECT name, host FROM images WHERE id = ?1", params![id], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .optional()
+    }
+}
@@ -0,0 +1,166 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, RwLock};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use job_scheduler::Schedule;
+use shiplift::Docker;
+
+use crate::{Image, ImageProvider};
+
+const BASE_BACKOFF_SECS : i64 = 5;
+const MAX_BACKOFF_SECS : i64 = 300;
+
+fn backoff_secs(consecutive_failures : u32) -> i64 {
+    (BASE_BACKOFF_SECS * 2i64.pow(consecutive_failures.min(6))).min(MAX_BACKOFF_SECS)
+}
+
+struct Health {
+    healthy : bool,
+    consecutive_failures : u32,
+    retry_after : Option<DateTime<Utc>>,
+}
+
+impl Default for Health {
+    fn default() -> Health {
+        Health { healthy: true, consecutive_failures: 0, retry_after: None }
+    }
+}
+
+pub struct ConfiguredEndpoint {
+    pub name : String,
+    pub docker : Docker,
+    pub schedule : Option<Schedule>,
+    health : Mutex<Health>,
+}
+
+impl ConfiguredEndpoint {
+    pub fn new(name : String, docker : Docker, schedule : Option<Schedule>) -> ConfiguredEndpoint {
+        ConfiguredEndpoint { name, docker, schedule, health: Mutex::new(Health::default()) }
+    }
+
+    // Cron cadence isn't checked here: a --host-schedule override gets its
+    // own job_scheduler::Job that fires this endpoint directly, so only
+    // backoff needs checking.
+    fn due(&self, now : DateTime<Utc>) -> bool {
+        match self.health.lock().unwrap().retry_after {
+            Some(retry_after) => now >= retry_after,
+            None => true,
+        }
+    }
+
+    async fn scan(&self, now : DateTime<Utc>) -> Option<HashSet<Image>> {
+        if !self.due(now) {
+            return None;
+        }
+
+        match self.docker.containers().list(&Default::default()).await {
+            Ok(containers) => {
+                let mut health = self.health.lock().unwrap();
+                if !health.healthy {
+                    println!("Endpoint '{}' is healthy again", self.name);
+                }
+                *health = Health::default();
+                drop(health);
+
+                let images = containers.into_iter()
+                    .map(|c| Image::new(&c.image, &c.image_id, &self.name))
+                    .collect();
+                Some(images)
+            }
+
+            Err(e) => {
+                let mut health = self.health.lock().unwrap();
+                health.healthy = false;
+                health.consecutive_failures += 1;
+                let backoff = backoff_secs(health.consecutive_failures);
+                health.retry_after = Some(now + chrono::Duration::seconds(backoff));
+
+                eprintln!(
+                    "Endpoint '{}' is unhealthy ({}), retrying in {}s",
+                    self.name, e, backoff,
+                );
+
+                None
+            }
+        }
+    }
+}
+
+// Endpoints are individually Arc-wrapped so a snapshot can be cloned out
+// from under the RwLock cheaply: RwLockReadGuard is !Send and can't be
+// held across an .await.
+#[derive(Clone)]
+pub struct EndpointPool(Arc<RwLock<Vec<Arc<ConfiguredEndpoint>>>>);
+
+impl EndpointPool {
+    pub fn new(endpoints : Vec<Arc<ConfiguredEndpoint>>) -> EndpointPool {
+        EndpointPool(Arc::new(RwLock::new(endpoints)))
+    }
+}
+
+#[async_trait]
+impl ImageProvider for EndpointPool {
+    // Skips endpoints with their own --host-schedule override; those are
+    // scanned by a dedicated Job instead (see the impl below).
+    async fn get_image_list(&self) -> Option<HashSet<Image>> {
+        let now = Utc::now();
+        let endpoints : Vec<Arc<ConfiguredEndpoint>> = self.0.read().unwrap().clone();
+
+        let mut images = HashSet::new();
+        for endpoint in endpoints.iter().filter(|e| e.schedule.is_none()) {
+            if let Some(new_images) = endpoint.scan(now).await {
+                images.extend(new_images.into_iter());
+            }
+        }
+
+        Some(images)
+    }
+}
+
+#[async_trait]
+impl ImageProvider for ConfiguredEndpoint {
+    async fn get_image_list(&self) -> Option<HashSet<Image>> {
+        Some(self.scan(Utc::now()).await.unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn endpoint() -> ConfiguredEndpoint {
+        ConfiguredEndpoint::new("test".to_string(), Docker::new(), None)
+    }
+
+    #[test]
+    fn due_when_no_backoff_recorded() {
+        assert!(endpoint().due(Utc::now()));
+    }
+
+    #[test]
+    fn not_due_before_retry_after() {
+        let e = endpoint();
+        let now = Utc::now();
+        e.health.lock().unwrap().retry_after = Some(now + chrono::Duration::seconds(30));
+
+        assert!(!e.due(now));
+    }
+
+    #[test]
+    fn due_once_retry_after_elapses() {
+        let e = endpoint();
+        let now = Utc::now();
+        e.health.lock().unwrap().retry_after = Some(now - chrono::Duration::seconds(1));
+
+        assert!(e.due(now));
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        assert_eq!(backoff_secs(0), 5);
+        assert_eq!(backoff_secs(1), 10);
+        assert_eq!(backoff_secs(2), 20);
+        assert_eq!(backoff_secs(10), MAX_BACKOFF_SECS);
+    }
+}
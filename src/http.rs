@@ -0,0 +1,131 @@
+use std::sync::Arc;
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{Html, IntoResponse};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use chrono::{DateTime, Utc};
+use job_scheduler::Schedule;
+use serde_json::json;
+use tokio::sync::{Mutex, RwLock};
+
+use crate::dbctx::DbCtx;
+use crate::endpoint::EndpointPool;
+use crate::notifier::Notifier;
+
+/// Everything a manual `/scan` trigger and the cron job need to run a
+/// scan. `db` and `servers` already synchronize themselves internally, so
+/// they're held directly rather than behind a lock of their own; `scan_lock`
+/// only serializes full scan runs against each other (so a manual `/scan`
+/// and the cron tick can't run at once), and `last_run` is behind its own
+/// lightweight lock so `/status` can read it without waiting on an
+/// in-progress scan.
+pub struct SharedState {
+    pub db : DbCtx,
+    pub servers : EndpointPool,
+    pub notifier : Box<dyn Notifier + Send + Sync>,
+    pub notify_template : String,
+    pub severities : Vec<String>,
+    pub digest : bool,
+    pub concurrency : usize,
+    pub schedule : Schedule,
+    scan_lock : Mutex<()>,
+    last_run : RwLock<Option<DateTime<Utc>>>,
+}
+
+impl SharedState {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        db : DbCtx,
+        servers : EndpointPool,
+        notifier : Box<dyn Notifier + Send + Sync>,
+        notify_template : String,
+        severities : Vec<String>,
+        digest : bool,
+        concurrency : usize,
+        schedule : Schedule,
+    ) -> SharedState {
+        SharedState {
+            db, servers, notifier, notify_template, severities, digest, concurrency, schedule,
+            scan_lock: Mutex::new(()),
+            last_run: RwLock::new(None),
+        }
+    }
+}
+
+pub type SharedStateHandle = Arc<SharedState>;
+
+/// Runs one scan-and-notify cycle against `image_provider`, holding
+/// `scan_lock` for its duration so a concurrent manual `/scan` and the
+/// cron tick (global or per-host) can't run at the same time.
+async fn run_scan<P : crate::ImageProvider>(state : &SharedStateHandle, image_provider : &P) {
+    let _guard = state.scan_lock.lock().await;
+
+    println!("Running trivy\n");
+    crate::run_checker(
+        image_provider,
+        state.notifier.as_ref(),
+        &state.notify_template,
+        &state.db,
+        &state.severities,
+        state.digest,
+        state.concurrency,
+    ).await;
+
+    *state.last_run.write().await = Some(Utc::now());
+    println!("Next run scheduled for {}", state.schedule.upcoming(Utc).next().unwrap());
+}
+
+/// Scans the whole pool. Used by the global `--schedule` tick and by a
+/// manual `/scan` trigger.
+pub async fn run_once(state : &SharedStateHandle) {
+    run_scan(state, &state.servers).await;
+}
+
+/// Scans a single endpoint. Used by the dedicated `job_scheduler::Job`
+/// registered for each `--host-schedule` override, so that host's cadence
+/// doesn't wait on the global tick.
+pub async fn run_for_endpoint(state : &SharedStateHandle, endpoint : &crate::endpoint::ConfiguredEndpoint) {
+    run_scan(state, endpoint).await;
+}
+
+/// Triggers a scan in the background and responds immediately, so a
+/// dashboard polling `/status` isn't blocked behind the scan itself.
+async fn trigger_scan(State(state) : State<SharedStateHandle>) -> impl IntoResponse {
+    tokio::spawn(async move { run_once(&state).await; });
+    Json(json!({ "status": "started" }))
+}
+
+async fn status(State(state) : State<SharedStateHandle>) -> impl IntoResponse {
+    let last_run = *state.last_run.read().await;
+
+    let images : Vec<_> = state.db.latest_scan_states().unwrap_or_default()
+        .into_iter()
+        .map(|(id, name, host, vulnerable)| json!({ "id": id, "name": name, "host": host, "vulnerable": vulnerable }))
+        .collect();
+
+    Json(json!({
+        "last_run": last_run.map(|t| t.to_rfc3339()),
+        "next_run": state.schedule.upcoming(Utc).next().map(|t| t.to_rfc3339()),
+        "images": images,
+    }))
+}
+
+async fn report(Path(id) : Path<String>) -> impl IntoResponse {
+    match tokio::fs::read(format!("/output/{}.html", id)).await {
+        Ok(bytes) => (StatusCode::OK, Html(bytes)).into_response(),
+        Err(_) => (StatusCode::NOT_FOUND, "no report for that image id").into_response(),
+    }
+}
+
+/// Builds the axum app exposing `/scan`, `/status` and `/report/:id`,
+/// enabled with `--listen ADDR` so the tool can be driven from a
+/// dashboard or CI without restarting it.
+pub fn router(state : SharedStateHandle) -> Router {
+    Router::new()
+        .route("/scan", post(trigger_scan))
+        .route("/status", get(status))
+        .route("/report/:id", get(report))
+        .with_state(state)
+}
@@ -1,9 +1,8 @@
 
 use std::collections::{HashMap, HashSet};
-use std::env;
 use std::hash::{Hash, Hasher};
-use std::process::Command;
 use std::str::FromStr;
+use std::sync::Arc;
 use std::time::Duration;
 
 use async_trait::async_trait;
@@ -11,22 +10,38 @@ use clap::{Arg, App};
 use chrono::Utc;
 use job_scheduler::{JobScheduler, Job, Schedule};
 use shiplift::Docker;
+use tokio::sync::Semaphore;
+
+mod dbctx;
+mod endpoint;
+mod http;
+mod notifier;
+mod trivy;
+use dbctx::DbCtx;
+use endpoint::{ConfiguredEndpoint, EndpointPool};
+use notifier::Notifier;
+use trivy::ScanResult;
 
 
 static DEFAULT_NOTIFY_TEMPLATE : &str = "Vulnerabilities found in image '{name}'";
+static DEFAULT_DB_PATH : &str = "./state.db";
+static DEFAULT_SEVERITY : &str = "HIGH,CRITICAL";
+static DEFAULT_CONCURRENCY : &str = "4";
 
 
 struct Image {
-    name : String,
-    id : String,
+    pub(crate) name : String,
+    pub(crate) id : String,
+    pub(crate) host : String,
 }
 
 impl Image {
-    fn new(name : &str, id : &str) -> Image {
+    fn new(name : &str, id : &str, host : &str) -> Image {
         Image {
             name: name.to_string(),
             // Remove the "sha256:" from the front of the digest
             id: id.split(':').nth(1).unwrap().to_string(),
+            host: host.to_string(),
         }
     }
 }
@@ -49,78 +64,56 @@ trait ImageProvider {
     async fn get_image_list(&self) -> Option<HashSet<Image>>;
 }
 
-#[async_trait]
-impl ImageProvider for Docker {
-    async fn get_image_list(&self) -> Option<HashSet<Image>> {
-        let result = self.containers().list(&Default::default()).await;
-        match result {
-            Ok(container) => {
-                let images = container.into_iter().map(|c| {
-                        Image::new(&c.image, &c.image_id)
-                    }).collect();
-
-                Some(images)
-            }
 
-            Err(e) => {
-                eprintln!("Error fetching images: {}", e);
-                None
-            }
-        }
-    }
-}
+/// Scans every image returned by `image_provider`, running at most
+/// `concurrency` trivy invocations at once. Each scan runs on a blocking
+/// task (since `run_trivy` shells out via a blocking `Command`) behind a
+/// semaphore permit, so a host with dozens of containers doesn't
+/// oversubscribe trivy. A panic in one scan is reported and skipped
+/// rather than aborting the rest of the run; db writes happen back on
+/// this task as each scan is joined, in digest order.
+async fn check_images(image_provider : &impl ImageProvider, db : &DbCtx, severities : &[String], concurrency : usize) -> Vec<ScanResult> {
+    let mut images : Vec<Image> = image_provider.get_image_list().await.unwrap().into_iter().collect();
+    // HashSet iteration order is randomized per-process, so sort by digest
+    // before spawning to keep result (and notification/report) ordering
+    // deterministic across runs.
+    images.sort_by(|a, b| a.id.cmp(&b.id));
 
-#[async_trait]
-impl ImageProvider for Vec<Docker> {
-
-    async fn get_image_list(&self) -> Option<HashSet<Image>> {
-        let mut images = HashSet::new();
-        for server in self {
-            let newones = server.get_image_list().await;
-            if let Some(new_images) = newones {
-                images.extend(new_images.into_iter());
-            }
-        }
+    let semaphore = Arc::new(Semaphore::new(concurrency));
 
-        Some(images)
+    let mut tasks = Vec::new();
+    for image in images {
+        let semaphore = Arc::clone(&semaphore);
+        tasks.push(tokio::spawn(async move {
+            let _permit = semaphore.acquire_owned().await.expect("semaphore closed");
+            println!("Checking {}\n", image.name);
+            tokio::task::spawn_blocking(move || trivy::run_trivy(&image)).await
+        }));
     }
 
-}
-
-
-fn run_trivy(image : &Image) -> bool {
-    let mut trivy = Command::new("trivy");
-
-    let trivy_env : HashMap<String, String> =
-        env::vars().filter(|&(ref key, _)|
-            key.starts_with("TRIVY")
-        ).collect();
-
-    trivy.env_clear();
-    trivy.env("TRIVY_TEMPLATE", "@templates/html.tpl");
-    trivy.envs(&trivy_env);
-
-    trivy.arg("image");
-    trivy.arg("--format").arg("template");
-    trivy.arg("--exit-code").arg("1");
-    trivy.arg("--output").arg(format!("/output/{}.html", image.id));
-    trivy.arg(&image.name);
-
-    let output = trivy.output().expect("failed to run trivy");
-    println!("{}", String::from_utf8_lossy(&output.stdout));
-
-    return output.status.success();
-}
+    let mut vulnerable = Vec::new();
+    for task in tasks {
+        let scan = match task.await {
+            Ok(Ok(scan)) => scan,
+            Ok(Err(e)) => {
+                eprintln!("Scan panicked: {}", e);
+                continue;
+            }
+            Err(e) => {
+                eprintln!("Scan task panicked: {}", e);
+                continue;
+            }
+        };
 
+        let report_path = format!("/output/{}.html", scan.image.id);
+        let is_vulnerable = scan.is_vulnerable(severities);
 
-async fn check_images(image_provider : &impl ImageProvider) -> Vec<Image> {
-    let mut vulnerable = Vec::new();
+        if let Err(e) = db.record_scan(&scan.image, is_vulnerable, &report_path) {
+            eprintln!("Failed to record scan for {}: {}", scan.image.name, e);
+        }
 
-    let images = image_provider.get_image_list().await.unwrap();
-    for image in images {
-        println!("Checking {}\n", image.name);
-        if !run_trivy(&image) {
-            vulnerable.push(image);
+        if is_vulnerable {
+            vulnerable.push(scan);
         }
     }
 
@@ -128,36 +121,75 @@ async fn check_images(image_provider : &impl ImageProvider) -> Vec<Image> {
 }
 
 
-fn send_notification(image : &Image, notify_url : &str, notify_template : &str) {
-    let message = notify_template.clone()
-        .replace("{name}", &image.name)
-        .replace("{id}", &image.id);
+static RESOLVED_TEMPLATE : &str = "Image '{name}' is no longer flagged as vulnerable";
 
-    let status = Command::new("shoutrrr")
-        .arg("send")
-        .arg("--url")
-        .arg(notify_url)
-        .arg("--message")
-        .arg(message)
-        .status();
-        
-    if status.is_err() || !status.unwrap().success() {
-        eprintln!("Failed to send notification");
+/// Sends `events` through `notifier`, batched into a single digest message
+/// when `--digest` is set, or one message per image otherwise.
+async fn send_events(notifier : &(dyn Notifier + Send + Sync), events : &[ScanResult], template : &str, digest : bool) {
+    if events.is_empty() {
+        return;
+    }
+
+    if digest {
+        notifier.notify(events, template).await;
+    } else {
+        for event in events {
+            notifier.notify(std::slice::from_ref(event), template).await;
+        }
     }
 }
 
 
-async fn run_checker(image_provider : &impl ImageProvider, notify_url : &str, notify_template : &str) {
-    let vulnerable = check_images(image_provider).await;
+async fn run_checker(image_provider : &impl ImageProvider, notifier : &(dyn Notifier + Send + Sync), notify_template : &str, db : &DbCtx, severities : &[String], digest : bool, concurrency : usize) {
+    let previously_vulnerable = db.currently_vulnerable_ids().unwrap_or_default();
+
+    let vulnerable = check_images(image_provider, db, severities, concurrency).await;
 
     if vulnerable.len() == 0 {
         println!("No vulnerabilities found");
     }
 
-    for image in vulnerable {
-        println!("Found vulnerabilities in {}", image.name);
-        send_notification(&image, notify_url, notify_template);
+    let mut still_vulnerable = HashSet::new();
+    let mut newly_vulnerable = Vec::new();
+    for scan in vulnerable {
+        still_vulnerable.insert(scan.image.id.clone());
+
+        if previously_vulnerable.contains(&scan.image.id) {
+            println!("{} is still vulnerable, already notified", scan.image.name);
+            continue;
+        }
+
+        println!("Found new vulnerabilities in {}", scan.image.name);
+        newly_vulnerable.push(scan);
     }
+
+    // Only images whose latest stored scan came back clean are resolved;
+    // images not rescanned this run (another host's, or an unreachable
+    // one's) keep their stored vulnerable state.
+    let latest_vulnerable = db.currently_vulnerable_ids().unwrap_or_else(|_| previously_vulnerable.clone());
+    let mut resolved = Vec::new();
+    for id in previously_vulnerable.difference(&still_vulnerable) {
+        if latest_vulnerable.contains(id) {
+            continue;
+        }
+        if let Ok(Some((name, host))) = db.image_info(id) {
+            resolved.push(ScanResult {
+                image: Image { name, id: id.clone(), host },
+                counts: Default::default(),
+                cve_ids: Vec::new(),
+            });
+        }
+    }
+
+    send_events(notifier, &newly_vulnerable, notify_template, digest).await;
+    send_events(notifier, &resolved, RESOLVED_TEMPLATE, digest).await;
+}
+
+
+/// Parses `--severity`'s comma-separated list into the uppercase form
+/// `ScanResult::is_vulnerable` compares against.
+fn parse_severities(raw : &str) -> Vec<String> {
+    raw.split(',').map(|s| s.trim().to_uppercase()).collect()
 }
 
 
@@ -177,15 +209,18 @@ fn main() {
             .long("notify-url")
             .takes_value(true)
             .max_values(1)
-            .help("shoutrrr url to send messages to"))
+            .help("Where to send notifications. A shoutrrr url, or a \
+                  'webhook+http(s)://...' url to POST JSON directly \
+                  without needing the shoutrrr binary installed"))
         .arg(Arg::with_name("template")
             .short("t")
             .long("notify-template")
             .takes_value(true)
             .max_values(1)
             .help("Message to send when vulnerabilities are found. \
-                  '{name}' and '{id}' are replaced with details of \
-                  the vulnerable image")
+                  '{name}', '{id}', '{critical}', '{high}', '{total}' \
+                  and '{cves}' are replaced with details of the \
+                  vulnerable image")
             .default_value(DEFAULT_NOTIFY_TEMPLATE))
         .arg(Arg::with_name("hosts")
             .short("H")
@@ -193,39 +228,165 @@ fn main() {
             .required(true)
             .takes_value(true)
             .min_values(1)
-            .help("Docker hosts to connect to"))
+            .help("Docker hosts to connect to. Each entry is a connection \
+                  URL, optionally prefixed with a label: 'name@url'. The \
+                  label defaults to the URL and is substituted for \
+                  '{host}' in notification templates"))
+        .arg(Arg::with_name("host-schedule")
+            .long("host-schedule")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .help("Per-host cron override as 'name=schedule', overriding \
+                  --schedule for just that host. May be given multiple \
+                  times"))
+        .arg(Arg::with_name("db-path")
+            .long("db-path")
+            .takes_value(true)
+            .max_values(1)
+            .help("Path to the SQLite scan history database")
+            .default_value(DEFAULT_DB_PATH))
+        .arg(Arg::with_name("severity")
+            .long("severity")
+            .takes_value(true)
+            .max_values(1)
+            .help("Comma-separated severities (e.g. HIGH,CRITICAL) that \
+                  mark an image as vulnerable")
+            .default_value(DEFAULT_SEVERITY))
+        .arg(Arg::with_name("digest")
+            .long("digest")
+            .takes_value(false)
+            .help("Summarize all vulnerable images from a run into a \
+                  single notification instead of one per image"))
+        .arg(Arg::with_name("concurrency")
+            .long("concurrency")
+            .takes_value(true)
+            .max_values(1)
+            .help("Maximum number of trivy scans to run at once")
+            .default_value(DEFAULT_CONCURRENCY))
+        .arg(Arg::with_name("listen")
+            .long("listen")
+            .takes_value(true)
+            .max_values(1)
+            .help("Address (e.g. 0.0.0.0:8080) to serve an HTTP control \
+                  endpoint on: POST /scan triggers an immediate run, \
+                  GET /status reports last/next run info, and \
+                  GET /report/{id} serves a generated report"))
         .get_matches();
 
     let schedule = matches.value_of("schedule").unwrap();
     let notify_url = matches.value_of("url").unwrap();
     let notify_template = matches.value_of("template").unwrap();
     let hosts = matches.values_of("hosts").unwrap();
+    let db_path = matches.value_of("db-path").unwrap();
+    let severities = parse_severities(matches.value_of("severity").unwrap());
+    let digest = matches.is_present("digest");
+    let concurrency : usize = matches.value_of("concurrency").unwrap()
+        .parse()
+        .expect("--concurrency must be a positive integer");
+    if concurrency == 0 {
+        panic!("--concurrency must be at least 1, got 0");
+    }
+    let listen_addr = matches.value_of("listen").map(|s| s.to_string());
+
+    let db = DbCtx::open(db_path).expect("failed to open state db");
+    let notifier = notifier::from_url(notify_url);
+
+    let mut host_schedules : HashMap<String, Schedule> = HashMap::new();
+    if let Some(entries) = matches.values_of("host-schedule") {
+        for entry in entries {
+            let (name, cron) = entry.split_once('=')
+                .unwrap_or_else(|| panic!("--host-schedule '{}' must be 'name=schedule'", entry));
+            let schedule = Schedule::from_str(cron)
+                .unwrap_or_else(|_| panic!("invalid cron schedule for host '{}': '{}'", name, cron));
+            host_schedules.insert(name.to_string(), schedule);
+        }
+    }
 
-    let mut servers = Vec::new();
+    let mut endpoints = Vec::new();
     for host in hosts {
-        if let Some(path) = host.strip_prefix("unix://") {
-            servers.push(Docker::unix(path));
+        let (name, url) = host.split_once('@').unwrap_or((host, host));
+        let docker = if let Some(path) = url.strip_prefix("unix://") {
+            Docker::unix(path)
         } else {
-            servers.push(Docker::host(host.parse().expect("Invalid host URL")));
-        }
+            Docker::host(url.parse().expect("Invalid host URL"))
+        };
+
+        let endpoint = ConfiguredEndpoint::new(name.to_string(), docker, host_schedules.remove(name));
+        endpoints.push(Arc::new(endpoint));
     }
 
-    let rt = tokio::runtime::Runtime::new().unwrap();
-    let mut scheduler = JobScheduler::new();
+    // Endpoints with their own `--host-schedule` get a dedicated `Job`
+    // below so their cadence isn't capped at the global `--schedule` tick.
+    let scheduled_endpoints : Vec<Arc<ConfiguredEndpoint>> = endpoints.iter()
+        .filter(|e| e.schedule.is_some())
+        .cloned()
+        .collect();
+    let servers = EndpointPool::new(endpoints);
 
     let schedule = Schedule::from_str(schedule).unwrap();
     println!("Next run scheduled for {}", schedule.upcoming(Utc).next().unwrap());
 
-    scheduler.add(Job::new(schedule.clone(), move || {
-        rt.block_on(async {
-            println!("Running trivy\n");
-            run_checker(&servers, notify_url, notify_template).await;
-            println!("Next run scheduled for {}", schedule.upcoming(Utc).next().unwrap());
+    let shared = Arc::new(http::SharedState::new(
+        db,
+        servers,
+        notifier,
+        notify_template.to_string(),
+        severities,
+        digest,
+        concurrency,
+        schedule.clone(),
+    ));
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let handle = rt.handle().clone();
+    let mut scheduler = JobScheduler::new();
+
+    if let Some(addr) = listen_addr {
+        let shared = Arc::clone(&shared);
+        rt.spawn(async move {
+            let listener = tokio::net::TcpListener::bind(&addr).await.expect("failed to bind --listen address");
+            println!("Listening on {}", addr);
+            axum::serve(listener, http::router(shared)).await.expect("http server failed");
         });
+    }
+
+    scheduler.add(Job::new(schedule, {
+        let shared = Arc::clone(&shared);
+        let handle = handle.clone();
+        move || {
+            handle.block_on(http::run_once(&shared));
+        }
     }));
 
+    for endpoint in scheduled_endpoints {
+        let host_schedule = endpoint.schedule.clone().unwrap();
+        scheduler.add(Job::new(host_schedule, {
+            let shared = Arc::clone(&shared);
+            let handle = handle.clone();
+            move || {
+                handle.block_on(http::run_for_endpoint(&shared, &endpoint));
+            }
+        }));
+    }
+
     loop {
         scheduler.tick();
         std::thread::sleep(Duration::from_secs(1));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_severities_trims_and_uppercases() {
+        assert_eq!(parse_severities("high, critical"), vec!["HIGH", "CRITICAL"]);
+    }
+
+    #[test]
+    fn parse_severities_single_value() {
+        assert_eq!(parse_severities("medium"), vec!["MEDIUM"]);
+    }
+}
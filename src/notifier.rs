@@ -0,0 +1,186 @@
+use async_trait::async_trait;
+use serde::Serialize;
+
+use crate::trivy::ScanResult;
+
+/// A destination for scan outcomes. `events` is a batch rather than a
+/// single image so a digest-mode run can summarize many images in one
+/// message instead of sending one per image.
+#[async_trait]
+pub trait Notifier {
+    async fn notify(&self, events : &[ScanResult], template : &str);
+}
+
+/// Renders `template`'s `{name}`/`{id}`/`{host}`/`{critical}`/`{high}`/
+/// `{total}`/`{cves}` placeholders for a single scan result.
+fn render(template : &str, scan : &ScanResult) -> String {
+    template
+        .replace("{name}", &scan.image.name)
+        .replace("{id}", &scan.image.id)
+        .replace("{host}", &scan.image.host)
+        .replace("{critical}", &scan.counts.critical.to_string())
+        .replace("{high}", &scan.counts.high.to_string())
+        .replace("{total}", &scan.counts.total().to_string())
+        .replace("{cves}", &scan.cve_ids.join(", "))
+}
+
+/// Renders one line per event and joins them, so a digest of several
+/// images still goes out as a single message.
+fn render_all(template : &str, events : &[ScanResult]) -> String {
+    events.iter().map(|scan| render(template, scan)).collect::<Vec<_>>().join("\n")
+}
+
+/// Wraps the `shoutrrr` CLI binary, exactly as trivy-scheduler has always
+/// sent notifications. Selected whenever `--notify-url` isn't a
+/// `webhook+` URL.
+pub struct ShoutrrrCli {
+    url : String,
+}
+
+impl ShoutrrrCli {
+    pub fn new(url : String) -> ShoutrrrCli {
+        ShoutrrrCli { url }
+    }
+}
+
+#[async_trait]
+impl Notifier for ShoutrrrCli {
+    async fn notify(&self, events : &[ScanResult], template : &str) {
+        if events.is_empty() {
+            return;
+        }
+
+        let message = render_all(template, events);
+        let url = self.url.clone();
+
+        // Shells out via a blocking `Command`, same as `trivy::run_trivy`,
+        // so it needs `spawn_blocking` too rather than stalling a tokio
+        // worker thread for the subprocess's lifetime.
+        let status = tokio::task::spawn_blocking(move || {
+            std::process::Command::new("shoutrrr")
+                .arg("send")
+                .arg("--url")
+                .arg(&url)
+                .arg("--message")
+                .arg(message)
+                .status()
+        }).await;
+
+        match status {
+            Ok(Ok(status)) if status.success() => {}
+            _ => eprintln!("Failed to send notification"),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct WebhookEvent {
+    name : String,
+    id : String,
+    host : String,
+    critical : u32,
+    high : u32,
+    medium : u32,
+    low : u32,
+    unknown : u32,
+    total : u32,
+    cves : Vec<String>,
+}
+
+impl From<&ScanResult> for WebhookEvent {
+    fn from(scan : &ScanResult) -> WebhookEvent {
+        WebhookEvent {
+            name: scan.image.name.clone(),
+            id: scan.image.id.clone(),
+            host: scan.image.host.clone(),
+            critical: scan.counts.critical,
+            high: scan.counts.high,
+            medium: scan.counts.medium,
+            low: scan.counts.low,
+            unknown: scan.counts.unknown,
+            total: scan.counts.total(),
+            cves: scan.cve_ids.clone(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct WebhookPayload {
+    message : String,
+    events : Vec<WebhookEvent>,
+}
+
+/// Native webhook backend: POSTs a JSON payload directly, so no external
+/// `shoutrrr` binary needs to be installed. Selected when `--notify-url`
+/// starts with `webhook+`, e.g. `webhook+https://hooks.example.com/...`.
+pub struct Webhook {
+    url : String,
+    client : reqwest::Client,
+}
+
+impl Webhook {
+    pub fn new(url : String) -> Webhook {
+        Webhook { url, client: reqwest::Client::new() }
+    }
+}
+
+#[async_trait]
+impl Notifier for Webhook {
+    async fn notify(&self, events : &[ScanResult], template : &str) {
+        if events.is_empty() {
+            return;
+        }
+
+        let payload = WebhookPayload {
+            message: render_all(template, events),
+            events: events.iter().map(WebhookEvent::from).collect(),
+        };
+
+        match self.client.post(&self.url).json(&payload).send().await {
+            Ok(response) if !response.status().is_success() => {
+                eprintln!("Webhook notification failed with status {}", response.status());
+            }
+            Err(e) => eprintln!("Failed to send webhook notification: {}", e),
+            Ok(_) => {}
+        }
+    }
+}
+
+/// Picks a `Notifier` backend based on `--notify-url`'s scheme: a
+/// `webhook+` prefix selects the native [`Webhook`] backend, using the
+/// remainder of the URL as the real endpoint; anything else is passed
+/// straight through to the [`ShoutrrrCli`] backend.
+pub fn from_url(url : &str) -> Box<dyn Notifier + Send + Sync> {
+    if let Some(real_url) = url.strip_prefix("webhook+") {
+        Box::new(Webhook::new(real_url.to_string()))
+    } else {
+        Box::new(ShoutrrrCli::new(url.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trivy::SeverityCounts;
+    use crate::Image;
+
+    fn scan(name : &str, host : &str) -> ScanResult {
+        ScanResult {
+            image: Image { name: name.to_string(), id: "abc123".to_string(), host: host.to_string() },
+            counts: SeverityCounts { critical: 2, high: 1, medium: 0, low: 0, unknown: 0 },
+            cve_ids: vec!["CVE-2024-0001".to_string()],
+        }
+    }
+
+    #[test]
+    fn render_fills_placeholders() {
+        let out = render("{name}@{host}: {critical} critical, {total} total ({cves})", &scan("nginx", "prod-1"));
+        assert_eq!(out, "nginx@prod-1: 2 critical, 3 total (CVE-2024-0001)");
+    }
+
+    #[test]
+    fn render_all_joins_one_line_per_event() {
+        let events = vec![scan("nginx", "prod-1"), scan("redis", "prod-2")];
+        assert_eq!(render_all("{name}", &events), "nginx\nredis");
+    }
+}
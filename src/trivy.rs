@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::env;
+use std::process::Command;
+
+use serde::Deserialize;
+
+use crate::Image;
+
+/// Shape of `trivy image --format json` output, trimmed to the fields we
+/// actually read.
+#[derive(Deserialize)]
+struct TrivyReport {
+    #[serde(rename = "Results")]
+    results: Option<Vec<TrivyResult>>,
+}
+
+#[derive(Deserialize)]
+struct TrivyResult {
+    #[serde(rename = "Vulnerabilities")]
+    vulnerabilities: Option<Vec<TrivyVulnerability>>,
+}
+
+#[derive(Deserialize)]
+struct TrivyVulnerability {
+    #[serde(rename = "VulnerabilityID")]
+    vulnerability_id: String,
+    #[serde(rename = "Severity")]
+    severity: String,
+}
+
+/// Per-severity finding counts for a single scan.
+#[derive(Default, Clone)]
+pub struct SeverityCounts {
+    pub critical : u32,
+    pub high : u32,
+    pub medium : u32,
+    pub low : u32,
+    pub unknown : u32,
+}
+
+impl SeverityCounts {
+    fn record(&mut self, severity : &str) {
+        match severity {
+            "CRITICAL" => self.critical += 1,
+            "HIGH" => self.high += 1,
+            "MEDIUM" => self.medium += 1,
+            "LOW" => self.low += 1,
+            _ => self.unknown += 1,
+        }
+    }
+
+    pub fn total(&self) -> u32 {
+        self.critical + self.high + self.medium + self.low + self.unknown
+    }
+
+    fn count_for(&self, severity : &str) -> u32 {
+        match severity {
+            "CRITICAL" => self.critical,
+            "HIGH" => self.high,
+            "MEDIUM" => self.medium,
+            "LOW" => self.low,
+            _ => self.unknown,
+        }
+    }
+}
+
+/// The outcome of scanning a single image.
+pub struct ScanResult {
+    pub image : Image,
+    pub counts : SeverityCounts,
+    pub cve_ids : Vec<String>,
+}
+
+impl ScanResult {
+    /// An image counts as vulnerable if it has any findings at one of the
+    /// given severity levels (e.g. `["HIGH", "CRITICAL"]`), rather than
+    /// any non-zero finding at all.
+    pub fn is_vulnerable(&self, severities : &[String]) -> bool {
+        severities.iter().any(|severity| self.counts.count_for(severity) > 0)
+    }
+}
+
+/// Runs trivy against `image` twice: once to produce the human-readable
+/// HTML report at `/output/{id}.html`, and once with `--format json` so we
+/// can parse per-severity counts and CVE ids out of the findings.
+pub fn run_trivy(image : &Image) -> ScanResult {
+    let trivy_env : HashMap<String, String> =
+        env::vars().filter(|&(ref key, _)|
+            key.starts_with("TRIVY")
+        ).collect();
+
+    let mut html_report = Command::new("trivy");
+    html_report.env_clear();
+    html_report.env("TRIVY_TEMPLATE", "@templates/html.tpl");
+    html_report.envs(&trivy_env);
+    html_report.arg("image");
+    html_report.arg("--format").arg("template");
+    html_report.arg("--output").arg(format!("/output/{}.html", image.id));
+    html_report.arg(&image.name);
+    let _ = html_report.output().expect("failed to run trivy");
+
+    let mut json_report = Command::new("trivy");
+    json_report.env_clear();
+    json_report.envs(&trivy_env);
+    json_report.arg("image");
+    json_report.arg("--format").arg("json");
+    json_report.arg(&image.name);
+    let output = json_report.output().expect("failed to run trivy");
+
+    let report : TrivyReport = serde_json::from_slice(&output.stdout)
+        .expect("failed to parse trivy json output");
+
+    let mut counts = SeverityCounts::default();
+    let mut cve_ids = Vec::new();
+    for result in report.results.unwrap_or_default() {
+        for vuln in result.vulnerabilities.unwrap_or_default() {
+            counts.record(&vuln.severity);
+            cve_ids.push(vuln.vulnerability_id);
+        }
+    }
+
+    ScanResult {
+        image: Image { name: image.name.clone(), id: image.id.clone(), host: image.host.clone() },
+        counts,
+        cve_ids,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn severity_counts_record_and_count_for() {
+        let mut counts = SeverityCounts::default();
+        counts.record("CRITICAL");
+        counts.record("HIGH");
+        counts.record("HIGH");
+        counts.record("bogus");
+
+        assert_eq!(counts.count_for("CRITICAL"), 1);
+        assert_eq!(counts.count_for("HIGH"), 2);
+        assert_eq!(counts.count_for("UNKNOWN"), 1);
+        assert_eq!(counts.total(), 4);
+    }
+
+    #[test]
+    fn is_vulnerable_checks_only_the_given_severities() {
+        let mut counts = SeverityCounts::default();
+        counts.record("MEDIUM");
+        let scan = ScanResult {
+            image: Image { name: "nginx".to_string(), id: "abc".to_string(), host: "host-1".to_string() },
+            counts,
+            cve_ids: Vec::new(),
+        };
+
+        assert!(!scan.is_vulnerable(&["HIGH".to_string(), "CRITICAL".to_string()]));
+        assert!(scan.is_vulnerable(&["MEDIUM".to_string()]));
+    }
+}